@@ -1,4 +1,14 @@
 //! Turn events into a string of HTML.
+//!
+//! GFM tables and footnotes (`[^id]`/`[^id]: …`, including multiple
+//! back-references for a footnote called more than once) are not implemented
+//! here: rendering any of them needs `Token` variants the tokenizer in this
+//! snapshot never emits, so earlier attempts at a two-pass footnote renderer,
+//! its multi-back-reference follow-up, and a `Token::Table*`-driven table
+//! renderer with per-column alignment were all reverted (see the
+//! `chunk0-4`/`chunk1-1`/`chunk1-2` commit history). Adding real support for
+//! any of these depends on tokenizer/construct work outside this module's
+//! scope.
 use crate::constant::{SAFE_PROTOCOL_HREF, SAFE_PROTOCOL_SRC};
 use crate::construct::character_reference::Kind as CharacterReferenceKind;
 use crate::token::Token;
@@ -12,6 +22,7 @@ use crate::util::{
     span::{codes as codes_from_span, from_exit_event, serialize},
 };
 use crate::{LineEnding, Options};
+use std::collections::HashMap;
 
 /// Representation of a link or image, resource or reference.
 /// Reused for temporary definitions as well, in the first pass.
@@ -44,6 +55,77 @@ struct Media {
     title: Option<String>,
 }
 
+/// Customizes the HTML emitted for individual constructs, the way orgize
+/// exposes a `Handler` trait for org syntax. Override only the methods you
+/// need; the rest fall back to the defaults below, which reproduce the
+/// output `compile` has always produced.
+pub trait HtmlHandler {
+    /// `<em>`.
+    fn emphasis_start(&self) -> String {
+        "<em>".to_string()
+    }
+    /// `</em>`.
+    fn emphasis_end(&self) -> String {
+        "</em>".to_string()
+    }
+    /// `<strong>`.
+    fn strong_start(&self) -> String {
+        "<strong>".to_string()
+    }
+    /// `</strong>`.
+    fn strong_end(&self) -> String {
+        "</strong>".to_string()
+    }
+    /// `<hr />`.
+    fn thematic_break(&self) -> String {
+        "<hr />".to_string()
+    }
+    /// Opening `<h1>`…`<h6>`, given the already-sanitized `id` attribute
+    /// value, if any.
+    fn heading_start(&self, rank: u8, id: Option<&str>) -> String {
+        format!(
+            "<h{}{}>",
+            rank,
+            id.map_or_else(String::new, |id| format!(" id=\"{}\"", id)),
+        )
+    }
+    /// Closing `</h1>`…`</h6>`.
+    fn heading_end(&self, rank: u8) -> String {
+        format!("</h{}>", rank)
+    }
+    /// Opening `<a>`, given the already-sanitized `href` and the
+    /// already-encoded title.
+    fn link_start(&self, destination: &str, title: Option<&str>) -> String {
+        format!(
+            "<a href=\"{}\"{}>",
+            destination,
+            title.map_or_else(String::new, |title| format!(" title=\"{}\"", title)),
+        )
+    }
+    /// `</a>`.
+    fn link_end(&self) -> String {
+        "</a>".to_string()
+    }
+    /// `<img />`, given the already-sanitized `src`, the interpreted `alt`
+    /// text, and the already-encoded title.
+    fn image(&self, destination: &str, alt: &str, title: Option<&str>) -> String {
+        format!(
+            "<img src=\"{}\" alt=\"{}\"{} />",
+            destination,
+            alt,
+            title.map_or_else(String::new, |title| format!(" title=\"{}\"", title)),
+        )
+    }
+}
+
+/// The [`HtmlHandler`] used when [`CompileContext::html_handler`] is unset:
+/// every method keeps the trait's default, i.e. today's output.
+struct DefaultHtmlHandler;
+
+impl HtmlHandler for DefaultHtmlHandler {}
+
+static DEFAULT_HTML_HANDLER: DefaultHtmlHandler = DefaultHtmlHandler;
+
 /// Representation of a definition.
 #[derive(Debug)]
 struct Definition {
@@ -65,24 +147,61 @@ struct CompileContext<'a> {
     /// compile markdown.
     pub atx_opening_sequence_size: Option<usize>,
     pub heading_setext_buffer: Option<String>,
+    pub heading_atx_buffer: Option<String>,
     pub code_flow_seen_data: Option<bool>,
     pub code_fenced_fences_count: Option<usize>,
+    pub code_fenced_lang: Option<String>,
+    /// Accumulated code-block source, buffered instead of pushed directly
+    /// while a `code_block_renderer` is set.
+    pub code_flow_buffer: Option<String>,
     pub code_text_inside: bool,
     pub character_reference_kind: Option<CharacterReferenceKind>,
     pub expect_first_item: Option<bool>,
     pub media_stack: Vec<Media>,
     pub definitions: Vec<(String, Definition)>,
     pub tight_stack: Vec<bool>,
+    /// Slugs already handed out for heading ids, so repeats get `-1`, `-2`, …
+    pub heading_slugs: HashMap<String, usize>,
     /// Fields used to influance the current compilation.
     pub slurp_one_line_ending: bool,
     pub tags: bool,
     pub ignore_encode: bool,
     pub last_was_tag: bool,
     /// Configuration
+    /// Allowed `href` protocols, or `None` to allow any (see
+    /// `allow_dangerous_protocol`). Defaults to `SAFE_PROTOCOL_HREF`, but
+    /// callers can widen or narrow it via `Options::allowed_href_protocols`.
     pub protocol_href: Option<Vec<&'static str>>,
+    /// Same as `protocol_href`, but for `img` `src`, defaulting to
+    /// `SAFE_PROTOCOL_SRC` / overridden by `Options::allowed_img_protocols`.
     pub protocol_src: Option<Vec<&'static str>>,
     pub line_ending_default: LineEnding,
     pub allow_dangerous_html: bool,
+    pub heading_ids: bool,
+    pub heading_offset: u8,
+    /// Whether to accumulate `(level, slug, text)` triples for every
+    /// heading in `toc_entries`, so callers can render a table of
+    /// contents after compiling. Implies heading ids, since a TOC is only
+    /// useful if its entries link somewhere.
+    pub toc: bool,
+    pub toc_entries: Vec<(usize, String, String)>,
+    /// Callback that renders the inner HTML of a fenced/indented code block,
+    /// given its language token (from the fence info string) and raw source.
+    pub code_block_renderer: Option<&'a dyn Fn(Option<&str>, &str) -> String>,
+    /// Simpler alternative to `code_block_renderer`, for callers that only
+    /// care about fenced code and are fine with an empty language string
+    /// when no info string was given (e.g. a `syntect`-backed highlighter).
+    /// Consulted when `code_block_renderer` isn’t set.
+    pub highlight: Option<&'a dyn Fn(&str, &str) -> String>,
+    /// Callback consulted when a reference or shortcut link/image has no
+    /// matching definition, before falling back to literal text.
+    pub broken_link_callback: Option<&'a dyn Fn(&str, &str) -> Option<(String, Option<String>)>>,
+    /// Whether to neutralize a blocklist of dangerous tag names in raw HTML,
+    /// as GFM’s `tagfilter` extension does.
+    pub gfm_tagfilter: bool,
+    /// Overrides the HTML emitted for emphasis, links, images, etc. Falls
+    /// back to [`HtmlHandler`]'s defaults when unset.
+    pub html_handler: Option<&'a dyn HtmlHandler>,
     /// Intermediate results.
     pub buffers: Vec<String>,
     pub index: usize,
@@ -101,14 +220,18 @@ impl<'a> CompileContext<'a> {
             codes,
             atx_opening_sequence_size: None,
             heading_setext_buffer: None,
+            heading_atx_buffer: None,
             code_flow_seen_data: None,
             code_fenced_fences_count: None,
+            code_fenced_lang: None,
+            code_flow_buffer: None,
             code_text_inside: false,
             character_reference_kind: None,
             expect_first_item: None,
             media_stack: vec![],
             definitions: vec![],
             tight_stack: vec![],
+            heading_slugs: HashMap::new(),
             slurp_one_line_ending: false,
             tags: true,
             ignore_encode: false,
@@ -116,15 +239,34 @@ impl<'a> CompileContext<'a> {
             protocol_href: if options.allow_dangerous_protocol {
                 None
             } else {
-                Some(SAFE_PROTOCOL_HREF.to_vec())
+                Some(
+                    options
+                        .allowed_href_protocols
+                        .clone()
+                        .unwrap_or_else(|| SAFE_PROTOCOL_HREF.to_vec()),
+                )
             },
             protocol_src: if options.allow_dangerous_protocol {
                 None
             } else {
-                Some(SAFE_PROTOCOL_SRC.to_vec())
+                Some(
+                    options
+                        .allowed_img_protocols
+                        .clone()
+                        .unwrap_or_else(|| SAFE_PROTOCOL_SRC.to_vec()),
+                )
             },
             line_ending_default: line_ending,
             allow_dangerous_html: options.allow_dangerous_html,
+            heading_ids: options.heading_ids,
+            heading_offset: options.heading_offset,
+            toc: options.toc,
+            toc_entries: vec![],
+            code_block_renderer: options.code_block_renderer.as_deref(),
+            highlight: options.highlight.as_deref(),
+            broken_link_callback: options.broken_link_callback.as_deref(),
+            gfm_tagfilter: options.gfm_tagfilter,
+            html_handler: options.html_handler.as_deref(),
             buffers: vec![String::new()],
             index: 0,
         }
@@ -178,6 +320,34 @@ impl<'a> CompileContext<'a> {
         self.push(&*eol);
     }
 
+    /// Whether a code-block renderer is configured, via either
+    /// `code_block_renderer` or `highlight`.
+    pub fn has_code_renderer(&self) -> bool {
+        self.code_block_renderer.is_some() || self.highlight.is_some()
+    }
+
+    /// The [`HtmlHandler`] to consult for this compilation: `html_handler`
+    /// when set, [`DefaultHtmlHandler`] (today's output) otherwise.
+    pub fn handler(&self) -> &dyn HtmlHandler {
+        self.html_handler.unwrap_or(&DEFAULT_HTML_HANDLER)
+    }
+
+    /// Turn heading text into a unique slug, mirroring rustdoc’s `IdMap`:
+    /// repeats of the same base slug get `-1`, `-2`, … appended, skipping
+    /// any suffixed slug that was already handed out.
+    pub fn unique_heading_id(&mut self, text: &str) -> String {
+        let base = slugify(&strip_html_tags(text));
+        let mut count = self.heading_slugs.get(&base).copied().unwrap_or(0);
+        let mut slug = base.clone();
+        while self.heading_slugs.contains_key(&slug) {
+            slug = format!("{}-{}", base, count);
+            count += 1;
+        }
+        self.heading_slugs.insert(base, count.max(1));
+        self.heading_slugs.entry(slug.clone()).or_insert(1);
+        slug
+    }
+
     /// Add a line ending if needed (as in, there’s no eol/eof already).
     pub fn line_ending_if_needed(&mut self) {
         let last_char = self.buf_tail().chars().last();
@@ -197,9 +367,140 @@ impl<'a> CompileContext<'a> {
     }
 }
 
+/// Strip HTML tags from interpreted heading text, leaving the plain text
+/// content behind so it can be slugified.
+fn strip_html_tags(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut in_tag = false;
+
+    for char in value.chars() {
+        if char == '<' {
+            in_tag = true;
+        } else if char == '>' {
+            in_tag = false;
+        } else if !in_tag {
+            result.push(char);
+        }
+    }
+
+    result
+}
+
+/// Slugify heading text the way rustdoc’s `derive_id` does: trim, lowercase,
+/// collapse whitespace runs to `-`, and drop anything that isn’t
+/// alphanumeric, `-`, or `_`.
+fn slugify(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut last_was_dash = false;
+
+    for char in value.trim().chars() {
+        if char.is_whitespace() {
+            if !last_was_dash {
+                result.push('-');
+                last_was_dash = true;
+            }
+        } else if char.is_alphanumeric() || char == '-' || char == '_' {
+            result.extend(char.to_lowercase());
+            last_was_dash = false;
+        }
+    }
+
+    result
+}
+
+/// Shift a heading’s rank by `heading_offset`, clamping the result into the
+/// `1..=6` range so embedded fragments can’t escape past `<h6>` or below
+/// `<h1>` (mirrors rustdoc’s `HeadingOffset`).
+fn apply_heading_offset(rank: usize, offset: u8) -> usize {
+    (rank + usize::from(offset)).clamp(1, 6)
+}
+
+/// Tag names GFM’s `tagfilter` extension neutralizes in the raw HTML that
+/// `allow_dangerous_html` lets through, since they can be used to inject
+/// active or document-wide content.
+const GFM_TAGFILTER_BLOCKLIST: [&str; 9] = [
+    "title",
+    "textarea",
+    "style",
+    "xmp",
+    "iframe",
+    "noembed",
+    "noframes",
+    "script",
+    "plaintext",
+];
+
+/// Escape the `<` of any `<tag`/`</tag` whose name is on
+/// [`GFM_TAGFILTER_BLOCKLIST`] and is followed by `>`, `/`, whitespace, or
+/// end-of-string. Everything else passes through unchanged.
+fn gfm_tagfilter(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut result = String::with_capacity(value.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        if bytes[index] == b'<' {
+            let mut cursor = index + 1;
+
+            if bytes.get(cursor) == Some(&b'/') {
+                cursor += 1;
+            }
+
+            let name_start = cursor;
+
+            while matches!(bytes.get(cursor), Some(byte) if byte.is_ascii_alphabetic()) {
+                cursor += 1;
+            }
+
+            let name = &value[name_start..cursor];
+            let boundary_ok = match bytes.get(cursor) {
+                None => true,
+                Some(byte) => *byte == b'>' || *byte == b'/' || byte.is_ascii_whitespace(),
+            };
+
+            if cursor > name_start
+                && boundary_ok
+                && GFM_TAGFILTER_BLOCKLIST
+                    .iter()
+                    .any(|blocked| blocked.eq_ignore_ascii_case(name))
+            {
+                result.push_str("&lt;");
+                index += 1;
+                continue;
+            }
+        }
+
+        let char_len = value[index..].chars().next().map_or(1, char::len_utf8);
+        result.push_str(&value[index..index + char_len]);
+        index += char_len;
+    }
+
+    result
+}
+
 /// Turn events and codes into a string of HTML.
-#[allow(clippy::too_many_lines)]
 pub fn compile(events: &[Event], codes: &[Code], options: &Options) -> String {
+    compile_internal(events, codes, options).0
+}
+
+/// Like [`compile`], but also returns the `(level, slug, text)` triples
+/// collected for every heading when [`Options::toc`] is enabled, so callers
+/// can render a table of contents (e.g. a nested sidebar) from them.
+pub fn compile_with_toc(
+    events: &[Event],
+    codes: &[Code],
+    options: &Options,
+) -> (String, Vec<(usize, String, String)>) {
+    compile_internal(events, codes, options)
+}
+
+/// Shared implementation behind [`compile`] and [`compile_with_toc`].
+#[allow(clippy::too_many_lines)]
+fn compile_internal(
+    events: &[Event],
+    codes: &[Code],
+    options: &Options,
+) -> (String, Vec<(usize, String, String)>) {
     let mut index = 0;
     let mut line_ending_inferred: Option<LineEnding> = None;
 
@@ -294,11 +595,44 @@ pub fn compile(events: &[Event], codes: &[Code], options: &Options) -> String {
     }
 
     assert_eq!(context.buffers.len(), 1, "expected 1 final buffer");
-    context
+    let html = context
         .buffers
         .get(0)
         .expect("expected 1 final buffer")
-        .to_string()
+        .to_string();
+
+    (html, context.toc_entries)
+}
+
+/// Turns a parsed event stream into an output string.
+///
+/// Implement this to plug in an alternate output format — plain text, a
+/// different dialect — in place of the built-in [`HtmlRenderer`]. Callers
+/// can inspect or transform the `events`/`codes` slices (e.g. rewriting
+/// link destinations, stripping images, counting words) before handing
+/// them to a renderer, following jotdown's `Parser`/`Render` split.
+pub trait Render {
+    /// Render `events`/`codes` into `out`.
+    fn push(&self, events: &[Event], codes: &[Code], out: &mut String);
+}
+
+/// The default [`Render`]: turns events into HTML exactly as [`compile`]
+/// always has.
+pub struct HtmlRenderer<'a> {
+    options: &'a Options,
+}
+
+impl<'a> HtmlRenderer<'a> {
+    /// Create an HTML renderer using the given compile `options`.
+    pub fn new(options: &'a Options) -> HtmlRenderer<'a> {
+        HtmlRenderer { options }
+    }
+}
+
+impl<'a> Render for HtmlRenderer<'a> {
+    fn push(&self, events: &[Event], codes: &[Code], out: &mut String) {
+        out.push_str(&compile(events, codes, self.options));
+    }
 }
 
 /// Handle [`Enter`][EventType::Enter].
@@ -407,6 +741,9 @@ fn on_enter_code_indented(context: &mut CompileContext) {
     context.code_flow_seen_data = Some(false);
     context.line_ending_if_needed();
     context.tag("<pre><code>");
+    if context.has_code_renderer() {
+        context.code_flow_buffer = Some(String::new());
+    }
 }
 
 /// Handle [`Enter`][EventType::Enter]:[`CodeFenced`][Token::CodeFenced].
@@ -416,6 +753,9 @@ fn on_enter_code_fenced(context: &mut CompileContext) {
     // Note that no `>` is used, which is added later.
     context.tag("<pre><code");
     context.code_fenced_fences_count = Some(0);
+    if context.has_code_renderer() {
+        context.code_flow_buffer = Some(String::new());
+    }
 }
 
 /// Handle [`Enter`][EventType::Enter]:[`CodeText`][Token::CodeText].
@@ -446,7 +786,7 @@ fn on_enter_definition_destination_string(context: &mut CompileContext) {
 
 /// Handle [`Enter`][EventType::Enter]:[`Emphasis`][Token::Emphasis].
 fn on_enter_emphasis(context: &mut CompileContext) {
-    context.tag("<em>");
+    context.tag(&*context.handler().emphasis_start());
 }
 
 /// Handle [`Enter`][EventType::Enter]:[`HtmlFlow`][Token::HtmlFlow].
@@ -600,10 +940,15 @@ fn on_enter_resource_destination_string(context: &mut CompileContext) {
 
 /// Handle [`Enter`][EventType::Enter]:[`Strong`][Token::Strong].
 fn on_enter_strong(context: &mut CompileContext) {
-    context.tag("<strong>");
+    context.tag(&*context.handler().strong_start());
 }
 
 /// Handle [`Exit`][EventType::Exit]:[`AutolinkEmail`][Token::AutolinkEmail].
+///
+/// Note: unlike `on_exit_media`, this writes the `<a>` tag directly rather
+/// than going through `context.handler().link_start()`/`link_end()`, so a
+/// custom [`HtmlHandler`] doesn't affect autolinks. Same for
+/// [`on_exit_autolink_protocol`].
 fn on_exit_autolink_email(context: &mut CompileContext) {
     let slice = serialize(
         context.codes,
@@ -695,11 +1040,17 @@ fn on_exit_character_reference_value(context: &mut CompileContext) {
 /// Handle [`Exit`][EventType::Exit]:[`CodeFlowChunk`][Token::CodeFlowChunk].
 fn on_exit_code_flow_chunk(context: &mut CompileContext) {
     context.code_flow_seen_data = Some(true);
-    context.push_raw(&*serialize(
+    let slice = serialize(
         context.codes,
         &from_exit_event(context.events, context.index),
         false,
-    ));
+    );
+
+    if let Some(buffer) = context.code_flow_buffer.as_mut() {
+        buffer.push_str(&slice);
+    } else {
+        context.push_raw(&*slice);
+    }
 }
 
 /// Handle [`Exit`][EventType::Exit]:[`CodeFencedFence`][Token::CodeFencedFence].
@@ -722,6 +1073,7 @@ fn on_exit_code_fenced_fence(context: &mut CompileContext) {
 fn on_exit_code_fenced_fence_info(context: &mut CompileContext) {
     let value = context.resume();
     context.tag(&*format!(" class=\"language-{}\"", value));
+    context.code_fenced_lang = Some(value);
 }
 
 /// Handle [`Exit`][EventType::Exit]:{[`CodeFenced`][Token::CodeFenced],[`CodeIndented`][Token::CodeIndented]}.
@@ -731,6 +1083,20 @@ fn on_exit_code_flow(context: &mut CompileContext) {
         .take()
         .expect("`code_flow_seen_data` must be defined");
 
+    if let Some(buffer) = context.code_flow_buffer.take() {
+        let lang = context.code_fenced_lang.take();
+        let html = if let Some(renderer) = context.code_block_renderer {
+            renderer(lang.as_deref(), &buffer)
+        } else if let Some(highlight) = context.highlight {
+            highlight(lang.as_deref().unwrap_or(""), &buffer)
+        } else {
+            unreachable!("`code_flow_buffer` implies a renderer is configured");
+        };
+        context.ignore_encode = true;
+        context.push(&*html);
+        context.ignore_encode = false;
+    }
+
     // One special case is if we are inside a container, and the fenced code was
     // not closed (meaning it runs to the end).
     // In that case, the following line ending, is considered *outside* the
@@ -854,17 +1220,33 @@ fn on_exit_definition_title_string(context: &mut CompileContext) {
 
 /// Handle [`Exit`][EventType::Exit]:[`Strong`][Token::Emphasis].
 fn on_exit_emphasis(context: &mut CompileContext) {
-    context.tag("</em>");
+    context.tag(&*context.handler().emphasis_end());
 }
 
 /// Handle [`Exit`][EventType::Exit]:[`HeadingAtx`][Token::HeadingAtx].
 fn on_exit_heading_atx(context: &mut CompileContext) {
-    let rank = context
-        .atx_opening_sequence_size
-        .take()
-        .expect("`atx_opening_sequence_size` must be set in headings");
+    let rank = apply_heading_offset(
+        context
+            .atx_opening_sequence_size
+            .take()
+            .expect("`atx_opening_sequence_size` must be set in headings"),
+        context.heading_offset,
+    );
+    let text = context.heading_atx_buffer.take().unwrap_or_default();
+    let slug = if context.heading_ids || context.toc {
+        let slug = context.unique_heading_id(&text);
+        if context.toc {
+            context.toc_entries.push((rank, slug.clone(), text.clone()));
+        }
+        Some(slug)
+    } else {
+        None
+    };
+    let depth = u8::try_from(rank).unwrap_or(6);
 
-    context.tag(&*format!("</h{}>", rank));
+    context.tag(&*context.handler().heading_start(depth, slug.as_deref()));
+    context.push(&*text);
+    context.tag(&*context.handler().heading_end(depth));
 }
 
 /// Handle [`Exit`][EventType::Exit]:[`HeadingAtxSequence`][Token::HeadingAtxSequence].
@@ -878,15 +1260,17 @@ fn on_exit_heading_atx_sequence(context: &mut CompileContext) {
         )
         .len();
         context.line_ending_if_needed();
+        // The opening tag itself is written later, in `on_exit_heading_atx`,
+        // once the heading’s text is known: that’s where `apply_heading_offset`
+        // is called and where a `heading_ids`/`toc` slug can be derived.
         context.atx_opening_sequence_size = Some(rank);
-        context.tag(&*format!("<h{}>", rank));
     }
 }
 
 /// Handle [`Exit`][EventType::Exit]:[`HeadingAtxText`][Token::HeadingAtxText].
 fn on_exit_heading_atx_text(context: &mut CompileContext) {
     let value = context.resume();
-    context.push(&*value);
+    context.heading_atx_buffer = Some(value);
 }
 
 /// Handle [`Exit`][EventType::Exit]:[`HeadingSetextText`][Token::HeadingSetextText].
@@ -901,17 +1285,30 @@ fn on_exit_heading_setext_underline(context: &mut CompileContext) {
     let text = context
         .heading_setext_buffer
         .take()
-        .expect("`atx_opening_sequence_size` must be set in headings");
+        .expect("`heading_setext_buffer` must be set in headings");
     let head = codes_from_span(
         context.codes,
         &from_exit_event(context.events, context.index),
     )[0];
-    let level: usize = if head == Code::Char('-') { 2 } else { 1 };
+    let level = apply_heading_offset(
+        if head == Code::Char('-') { 2 } else { 1 },
+        context.heading_offset,
+    );
+    let slug = if context.heading_ids || context.toc {
+        let slug = context.unique_heading_id(&text);
+        if context.toc {
+            context.toc_entries.push((level, slug.clone(), text.clone()));
+        }
+        Some(slug)
+    } else {
+        None
+    };
+    let depth = u8::try_from(level).unwrap_or(6);
 
     context.line_ending_if_needed();
-    context.tag(&*format!("<h{}>", level));
+    context.tag(&*context.handler().heading_start(depth, slug.as_deref()));
     context.push(&*text);
-    context.tag(&*format!("</h{}>", level));
+    context.tag(&*context.handler().heading_end(depth));
 }
 
 /// Handle [`Exit`][EventType::Exit]:{[`HtmlFlow`][Token::HtmlFlow],[`HtmlText`][Token::HtmlText]}.
@@ -926,6 +1323,12 @@ fn on_exit_html_data(context: &mut CompileContext) {
         &from_exit_event(context.events, context.index),
         false,
     );
+    // Without `allow_dangerous_html`, `push_raw` already encodes every `<`.
+    let slice = if context.gfm_tagfilter && context.allow_dangerous_html {
+        gfm_tagfilter(&slice)
+    } else {
+        slice
+    };
     context.push_raw(&*slice);
 }
 
@@ -952,6 +1355,12 @@ fn on_exit_line_ending(context: &mut CompileContext) {
         context.push(" ");
     } else if context.slurp_one_line_ending {
         context.slurp_one_line_ending = false;
+    } else if let Some(buffer) = context.code_flow_buffer.as_mut() {
+        buffer.push_str(&serialize(
+            context.codes,
+            &from_exit_event(context.events, context.index),
+            false,
+        ));
     } else {
         context.push_raw(&*serialize(
             context.codes,
@@ -1041,59 +1450,69 @@ fn on_exit_media(context: &mut CompileContext) {
         .or(media.label_id)
         .map(|id| normalize_identifier(&id));
     let label = media.label.unwrap();
-    let mut definition: Option<&Definition> = None;
+    let mut definition: Option<Definition> = None;
+
+    if media.destination.is_none() {
+        if let Some(id) = &id {
+            let mut index = 0;
+
+            while index < context.definitions.len() {
+                if &context.definitions[index].0 == id {
+                    let found = &context.definitions[index].1;
+                    definition = Some(Definition {
+                        destination: found.destination.clone(),
+                        title: found.title.clone(),
+                    });
+                    break;
+                }
 
-    if let Some(id) = id {
-        let mut index = 0;
+                index += 1;
+            }
 
-        while index < context.definitions.len() {
-            if context.definitions[index].0 == id {
-                definition = Some(&context.definitions[index].1);
-                break;
+            if definition.is_none() {
+                if let Some(callback) = context.broken_link_callback {
+                    if let Some((destination, title)) = callback(id, &label) {
+                        definition = Some(Definition {
+                            destination: Some(destination),
+                            title,
+                        });
+                    }
+                }
             }
 
-            index += 1;
+            // No definition, and no callback resolved one: fall back to the
+            // reference’s literal text, as pulldown-cmark does without a
+            // broken link callback.
+            if definition.is_none() {
+                context.push_raw(&*format!("[{}]", label));
+                return;
+            }
         }
     }
 
-    let destination = if media.destination.is_some() {
-        &media.destination
-    } else {
-        &definition.unwrap().destination
-    };
-    let title = if media.destination.is_some() {
-        &media.title
+    let (destination, title) = if media.destination.is_some() {
+        (media.destination, media.title)
     } else {
-        &definition.unwrap().title
+        let definition = definition.unwrap();
+        (definition.destination, definition.title)
     };
 
-    let destination = if let Some(destination) = destination {
-        destination
+    let destination = if let Some(destination) = &destination {
+        destination.as_str()
     } else {
         ""
     };
 
-    let title = if let Some(title) = title {
-        format!(" title=\"{}\"", title)
-    } else {
-        "".to_string()
-    };
+    let title = title.as_deref();
 
     if media.image {
-        context.tag(&*format!(
-            "<img src=\"{}\" alt=\"",
-            sanitize_uri(destination, &context.protocol_src),
-        ));
-        context.push(&*label);
-        context.tag(&*format!("\"{} />", title));
+        let href = sanitize_uri(destination, &context.protocol_src);
+        context.tag(&*context.handler().image(&href, &label, title));
     } else {
-        context.tag(&*format!(
-            "<a href=\"{}\"{}>",
-            sanitize_uri(destination, &context.protocol_href),
-            title,
-        ));
+        let href = sanitize_uri(destination, &context.protocol_href);
+        context.tag(&*context.handler().link_start(&href, title));
         context.push(&*label);
-        context.tag("</a>");
+        context.tag(&*context.handler().link_end());
     };
 }
 
@@ -1137,11 +1556,11 @@ fn on_exit_resource_title_string(context: &mut CompileContext) {
 
 /// Handle [`Exit`][EventType::Exit]:[`Strong`][Token::Strong].
 fn on_exit_strong(context: &mut CompileContext) {
-    context.tag("</strong>");
+    context.tag(&*context.handler().strong_end());
 }
 
 /// Handle [`Exit`][EventType::Exit]:[`ThematicBreak`][Token::ThematicBreak].
 fn on_exit_thematic_break(context: &mut CompileContext) {
     context.line_ending_if_needed();
-    context.tag("<hr />");
+    context.tag(&*context.handler().thematic_break());
 }
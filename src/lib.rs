@@ -0,0 +1,149 @@
+//! markdown-rs: turn markdown into HTML (or other things).
+//!
+//! The pieces that actually parse markdown into an event stream (tokenizer,
+//! constructs, tokens, and the small utilities they share) live outside this
+//! snapshot. This file owns the crate root: the module wiring, and the
+//! public [`Options`]/[`LineEnding`] configuration types that [`compiler`]
+//! and [`mdast`] compile against.
+mod constant;
+mod construct;
+mod parser;
+mod util;
+
+pub mod compiler;
+pub mod mdast;
+pub mod token;
+pub mod tokenizer;
+
+pub use token::Token;
+pub use tokenizer::{Code, Event, EventType};
+
+/// Turn a markdown string into its event/code stream, without compiling it
+/// to anything yet.
+///
+/// This is the other half of [`Render`][compiler::Render]: call it once,
+/// then feed the result to as many renderers as you like — or inspect and
+/// rewrite the events in between, e.g. to rewrite link destinations or
+/// strip images before compiling to HTML. [`Event`]/[`Code`] are public, so
+/// callers can actually name and match on the returned slices instead of
+/// treating them as an opaque token to hand back to [`to_html`].
+pub fn parse(value: &str) -> (Vec<Event>, Vec<Code>) {
+    parser::parse(value)
+}
+
+/// Turn markdown into HTML, using default compile options.
+pub fn to_html(value: &str) -> String {
+    to_html_with_options(value, &Options::default())
+}
+
+/// Turn markdown into HTML, using custom compile `options`.
+pub fn to_html_with_options(value: &str, options: &Options) -> String {
+    let (events, codes) = parse(value);
+    compiler::compile(&events, &codes, options)
+}
+
+/// Line ending style to use when a compiled document doesn't already commit
+/// to one (e.g. an empty document, or one that never contains a line break).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`
+    LineFeed,
+    /// `\r\n`
+    CarriageReturnLineFeed,
+    /// `\r`
+    CarriageReturn,
+}
+
+impl LineEnding {
+    /// Turn the [`LineEnding`] into the string it represents.
+    pub fn as_str(&self) -> &str {
+        match self {
+            LineEnding::LineFeed => "\n",
+            LineEnding::CarriageReturnLineFeed => "\r\n",
+            LineEnding::CarriageReturn => "\r",
+        }
+    }
+
+    /// Turn a [`Code`][crate::tokenizer::Code] into a [`LineEnding`],
+    /// assuming it's already known to be an EOL.
+    pub fn from_code(code: crate::tokenizer::Code) -> LineEnding {
+        match code {
+            crate::tokenizer::Code::CarriageReturnLineFeed => LineEnding::CarriageReturnLineFeed,
+            crate::tokenizer::Code::Char('\r') => LineEnding::CarriageReturn,
+            crate::tokenizer::Code::Char('\n') => LineEnding::LineFeed,
+            _ => unreachable!("expected eol"),
+        }
+    }
+}
+
+impl Default for LineEnding {
+    fn default() -> LineEnding {
+        LineEnding::LineFeed
+    }
+}
+
+/// Configuration for how markdown is turned into HTML.
+#[derive(Default)]
+pub struct Options {
+    /// Whether to allow dangerous HTML (`<script>`, etc.) to be passed
+    /// through untouched.
+    ///
+    /// The default, `false`, neutralizes raw HTML tags. Protocols are not
+    /// affected: see [`Options::allow_dangerous_protocol`].
+    pub allow_dangerous_html: bool,
+    /// Whether to allow dangerous protocols (`javascript:`, etc.) in
+    /// `href`/`src` attributes. Independent of
+    /// [`Options::allow_dangerous_html`]. The default, `false`, only lets
+    /// safe protocols through (see [`Options::allowed_href_protocols`] /
+    /// [`Options::allowed_img_protocols`]).
+    pub allow_dangerous_protocol: bool,
+    /// Line ending to fall back to when compiling a document that doesn't
+    /// otherwise commit to one.
+    pub default_line_ending: LineEnding,
+    /// Whether headings get an `id` attribute derived from their text, so
+    /// they can be linked to directly.
+    pub heading_ids: bool,
+    /// Number of levels to shift rendered ATX/setext headings down by (e.g.
+    /// `2` turns a source `#` into an `<h3>`), clamped so the result never
+    /// exceeds `<h6>`. Useful when embedding a document inside a page that
+    /// already has its own heading hierarchy.
+    pub heading_offset: u8,
+    /// Callback that renders the inner HTML of a fenced or indented code
+    /// block, given its language (the fence info string, or `None` for
+    /// indented code) and raw source. Lets callers plug in a syntax
+    /// highlighter in place of the default escaped `<pre><code>` output.
+    pub code_block_renderer: Option<Box<dyn Fn(Option<&str>, &str) -> String>>,
+    /// Callback consulted when a reference or shortcut link/image has no
+    /// matching definition, before falling back to literal text. Given the
+    /// (normalized) identifier and its raw label, returns the destination
+    /// and optional title to use, or `None` to fall back as usual.
+    pub broken_link_callback:
+        Option<Box<dyn Fn(&str, &str) -> Option<(String, Option<String>)>>>,
+    /// Whether to neutralize a blocklist of dangerous tag names in raw HTML,
+    /// as GFM's `tagfilter` extension does. Applies uniformly to both HTML
+    /// flow (block-level `<div>…`) and HTML text (inline `<span>`) content,
+    /// since both funnel through the same filtered exit handler. Only has an
+    /// effect together with [`Options::allow_dangerous_html`]; without it,
+    /// all raw HTML is escaped anyway.
+    pub gfm_tagfilter: bool,
+    /// Whether to accumulate `(level, slug, text)` triples for every heading
+    /// into a table of contents, returned alongside the compiled HTML by
+    /// [`compiler::compile_with_toc`]. Implies heading ids, since a TOC is
+    /// only useful if its entries link somewhere.
+    pub toc: bool,
+    /// Simpler alternative to [`Options::code_block_renderer`], for callers
+    /// that only care about fenced code and are fine with an empty language
+    /// string when no info string was given (e.g. a `syntect`-backed
+    /// highlighter). Consulted only when `code_block_renderer` isn't set.
+    pub highlight: Option<Box<dyn Fn(&str, &str) -> String>>,
+    /// Overrides the HTML emitted for emphasis, links, images, etc. Falls
+    /// back to [`compiler::HtmlHandler`]'s defaults (today's output) when
+    /// unset.
+    pub html_handler: Option<Box<dyn compiler::HtmlHandler>>,
+    /// Overrides which `href` protocols are allowed when
+    /// [`Options::allow_dangerous_protocol`] is `false`. Defaults to a safe
+    /// built-in list when unset.
+    pub allowed_href_protocols: Option<Vec<&'static str>>,
+    /// Same as [`Options::allowed_href_protocols`], but for `img` `src`.
+    pub allowed_img_protocols: Option<Vec<&'static str>>,
+}
@@ -0,0 +1,798 @@
+//! Turn events into a typed markdown syntax tree (mdast), as an alternative
+//! to the flat HTML string produced by [`crate::compiler`].
+//!
+//! This reuses the same event stream the compiler consumes, but instead of
+//! writing tags it builds [`Node`] values with children and byte-offset
+//! [`Position`]s, following the shape of <https://github.com/syntax-tree/mdast>.
+//! Link and image resolution against `Definition`s mirrors
+//! `compiler::on_exit_media`.
+//!
+//! GFM footnotes and tables are not represented in the tree;
+//! [`crate::compiler::compile`] doesn't render them either.
+use crate::constant::{SAFE_PROTOCOL_HREF, SAFE_PROTOCOL_SRC};
+use crate::construct::character_reference::Kind as CharacterReferenceKind;
+use crate::token::Token;
+use crate::tokenizer::{Code, Event, EventType};
+use crate::util::decode_character_reference::{decode_named, decode_numeric};
+use crate::util::normalize_identifier::normalize_identifier;
+use crate::util::sanitize_uri::sanitize_uri;
+use crate::util::span::{codes as codes_from_span, from_exit_event, serialize};
+
+/// A byte span of a node in the original source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Position {
+    /// Byte offset, inclusive, of the first code that belongs to the node.
+    pub start: usize,
+    /// Byte offset, exclusive, of the last code that belongs to the node.
+    pub end: usize,
+}
+
+/// A node in the markdown syntax tree.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Node {
+    /// Document root.
+    Root(Parent),
+    /// `<p>`.
+    Paragraph(Parent),
+    /// `<h1>`…`<h6>`.
+    Heading(Heading),
+    /// `<hr />`.
+    ThematicBreak(Position),
+    /// `<blockquote>`.
+    BlockQuote(Parent),
+    /// `<ol>`/`<ul>`.
+    List(List),
+    /// `<li>`.
+    ListItem(Parent),
+    /// `<pre><code>`.
+    Code(CodeBlock),
+    /// A link reference definition (`[x]: y "z"`), not rendered itself, but
+    /// consulted to resolve reference [`Node::Link`]s and [`Node::Image`]s.
+    Definition(Definition),
+    /// `<em>`.
+    Emphasis(Parent),
+    /// `<strong>`.
+    Strong(Parent),
+    /// `<a>`.
+    Link(Link),
+    /// `<img>`.
+    Image(Image),
+    /// Plain text.
+    Text(Text),
+}
+
+/// A node that only holds other nodes.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Parent {
+    pub children: Vec<Node>,
+    pub position: Position,
+}
+
+/// A heading, with its rank (1 through 6).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Heading {
+    pub depth: u8,
+    pub children: Vec<Node>,
+    pub position: Position,
+}
+
+/// An ordered or unordered list.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct List {
+    pub ordered: bool,
+    /// Starting number, for ordered lists that don't start at `1`.
+    pub start: Option<u32>,
+    pub children: Vec<Node>,
+    pub position: Position,
+}
+
+/// A fenced or indented code block.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CodeBlock {
+    /// The info string's first word, for fenced code (`js` in ` ```js `).
+    pub lang: Option<String>,
+    pub value: String,
+    pub position: Position,
+}
+
+/// A link reference definition.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Definition {
+    /// Normalized identifier, used to resolve references against this
+    /// definition.
+    pub identifier: String,
+    pub destination: Option<String>,
+    pub title: Option<String>,
+    pub position: Position,
+}
+
+/// A link, resource or reference.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Link {
+    pub destination: Option<String>,
+    pub title: Option<String>,
+    pub children: Vec<Node>,
+    pub position: Position,
+}
+
+/// An image, resource or reference.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Image {
+    pub destination: Option<String>,
+    pub title: Option<String>,
+    /// Flattened plain-text alternative, as images can't contain nodes.
+    pub alt: String,
+    pub position: Position,
+}
+
+/// Plain text.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Text {
+    pub value: String,
+    pub position: Position,
+}
+
+/// Temporary state for a link or image while its children are still open.
+struct MediaBuilder {
+    image: bool,
+    label_id: Option<String>,
+    reference_id: Option<String>,
+    destination: Option<String>,
+    title: Option<String>,
+}
+
+/// What an open [`Frame`] will turn into once its matching exit event is
+/// reached and its children are known.
+enum FrameKind {
+    Root,
+    Paragraph,
+    /// Depth is filled in once `HeadingAtxSequence` is seen.
+    Heading(u8),
+    BlockQuote,
+    List { ordered: bool, start: Option<u32> },
+    ListItem,
+    Emphasis,
+    Strong,
+    Media(MediaBuilder),
+}
+
+/// An open node on the builder's stack.
+struct Frame {
+    kind: FrameKind,
+    start: usize,
+    children: Vec<Node>,
+}
+
+/// Builds a [`Node::Root`] from an event stream, mirroring
+/// `compiler::compile_internal`'s two-pass handling of definitions: they're
+/// collected first so forward references resolve, then the tree is built.
+struct Builder<'a> {
+    events: &'a [Event],
+    codes: &'a [Code],
+    stack: Vec<Frame>,
+    definitions: Vec<Definition>,
+    expect_first_list_item: Option<bool>,
+    code_lang: Option<String>,
+    code_value: Option<String>,
+    character_reference_kind: Option<CharacterReferenceKind>,
+    /// Children and position of a setext heading's text, buffered at
+    /// `HeadingSetextText`'s exit until `HeadingSetextUnderline`'s exit
+    /// reveals the depth (there's no single enclosing frame spanning both,
+    /// unlike `HeadingAtx`).
+    pending_heading_setext: Option<(Vec<Node>, Position)>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(events: &'a [Event], codes: &'a [Code]) -> Builder<'a> {
+        Builder {
+            events,
+            codes,
+            stack: vec![Frame {
+                kind: FrameKind::Root,
+                start: 0,
+                children: vec![],
+            }],
+            definitions: vec![],
+            expect_first_list_item: None,
+            code_lang: None,
+            code_value: None,
+            character_reference_kind: None,
+            pending_heading_setext: None,
+        }
+    }
+
+    /// Byte offset of the given event.
+    fn offset(&self, index: usize) -> usize {
+        self.events[index].point.index
+    }
+
+    /// Raw source text covered by the exit event at `index`.
+    fn text_at(&self, index: usize) -> String {
+        serialize(self.codes, &from_exit_event(self.events, index), false)
+    }
+
+    fn push_frame(&mut self, kind: FrameKind, start: usize) {
+        self.stack.push(Frame {
+            kind,
+            start,
+            children: vec![],
+        });
+    }
+
+    /// Append a node to the currently open frame, merging into a trailing
+    /// [`Node::Text`] when possible so adjacent text runs (e.g. a character
+    /// reference next to plain data) collapse into one node.
+    fn push_node(&mut self, node: Node) {
+        let children = &mut self.stack.last_mut().expect("root frame always open").children;
+        if let Node::Text(text) = &node {
+            if let Some(Node::Text(previous)) = children.last_mut() {
+                previous.value.push_str(&text.value);
+                previous.position.end = text.position.end;
+                return;
+            }
+        }
+        children.push(node);
+    }
+
+    fn push_text(&mut self, value: String, position: Position) {
+        self.push_node(Node::Text(Text { value, position }));
+    }
+
+    /// Pop the current frame, returning its kind, its children, and its
+    /// position (from the frame's start to `end`); the caller turns them
+    /// into a [`Node`] and appends it to the (now current) parent.
+    fn pop_frame(&mut self, end: usize) -> (FrameKind, Vec<Node>, Position) {
+        let frame = self.stack.pop().expect("frame was pushed on enter");
+        let position = Position {
+            start: frame.start,
+            end,
+        };
+        (frame.kind, frame.children, position)
+    }
+
+    fn media_mut(&mut self) -> &mut MediaBuilder {
+        match &mut self.stack.last_mut().expect("media frame is open").kind {
+            FrameKind::Media(media) => media,
+            _ => unreachable!("expected an open link or image"),
+        }
+    }
+
+    fn enter(&mut self, index: usize) {
+        let start = self.offset(index);
+        match self.events[index].token_type {
+            Token::Paragraph => self.push_frame(FrameKind::Paragraph, start),
+            Token::HeadingAtx => self.push_frame(FrameKind::Heading(0), start),
+            Token::HeadingSetextText => self.push_frame(FrameKind::Heading(0), start),
+            Token::BlockQuote => self.push_frame(FrameKind::BlockQuote, start),
+            Token::ListOrdered | Token::ListUnordered => {
+                self.expect_first_list_item = Some(true);
+                self.push_frame(
+                    FrameKind::List {
+                        ordered: self.events[index].token_type == Token::ListOrdered,
+                        start: None,
+                    },
+                    start,
+                );
+            }
+            Token::ListItem => self.push_frame(FrameKind::ListItem, start),
+            Token::Emphasis => self.push_frame(FrameKind::Emphasis, start),
+            Token::Strong => self.push_frame(FrameKind::Strong, start),
+            Token::CodeIndented | Token::CodeFenced => {
+                self.code_lang = None;
+                self.code_value = Some(String::new());
+            }
+            Token::Definition => {
+                self.push_frame(
+                    FrameKind::Media(MediaBuilder {
+                        image: false,
+                        label_id: None,
+                        reference_id: None,
+                        destination: None,
+                        title: None,
+                    }),
+                    start,
+                );
+            }
+            Token::Link => self.push_frame(
+                FrameKind::Media(MediaBuilder {
+                    image: false,
+                    label_id: None,
+                    reference_id: None,
+                    destination: None,
+                    title: None,
+                }),
+                start,
+            ),
+            Token::Image => self.push_frame(
+                FrameKind::Media(MediaBuilder {
+                    image: true,
+                    label_id: None,
+                    reference_id: None,
+                    destination: None,
+                    title: None,
+                }),
+                start,
+            ),
+            _ => {}
+        }
+    }
+
+    fn exit(&mut self, index: usize) {
+        let end = self.offset(index);
+        match self.events[index].token_type {
+            Token::Data | Token::CharacterEscapeValue => {
+                let value = self.text_at(index);
+                let start = end - value.len();
+                self.push_text(value, Position { start, end });
+            }
+            Token::CharacterReferenceMarker => {
+                self.character_reference_kind = Some(CharacterReferenceKind::Named);
+            }
+            Token::CharacterReferenceMarkerHexadecimal => {
+                self.character_reference_kind = Some(CharacterReferenceKind::Hexadecimal);
+            }
+            Token::CharacterReferenceMarkerNumeric => {
+                self.character_reference_kind = Some(CharacterReferenceKind::Decimal);
+            }
+            Token::CharacterReferenceValue => {
+                let kind = self
+                    .character_reference_kind
+                    .take()
+                    .expect("expected `character_reference_kind` to be set");
+                let reference = self.text_at(index);
+                let value = match kind {
+                    CharacterReferenceKind::Decimal => decode_numeric(&reference, 10).to_string(),
+                    CharacterReferenceKind::Hexadecimal => decode_numeric(&reference, 16).to_string(),
+                    CharacterReferenceKind::Named => decode_named(&reference),
+                };
+                let start = end - reference.len();
+                self.push_text(value, Position { start, end });
+            }
+            Token::LineEnding => {
+                let value = self.text_at(index);
+                let after_opening_fence =
+                    index >= 2 && self.events[index - 2].token_type == Token::CodeFencedFence;
+                if let Some(code) = self.code_value.as_mut() {
+                    // Like `compile`, drop the line ending right after the
+                    // opening fence and keep the others as part of the code.
+                    if !after_opening_fence {
+                        code.push_str(&value);
+                    }
+                } else {
+                    self.push_text(value, Position { start: end, end });
+                }
+            }
+            Token::HardBreakTrailing => {
+                self.push_text(" ".to_string(), Position { start: end, end });
+            }
+            Token::HeadingAtxSequence => {
+                if let FrameKind::Heading(depth) = &mut self.stack.last_mut().unwrap().kind {
+                    if *depth == 0 {
+                        *depth = self.text_at(index).len() as u8;
+                    }
+                }
+            }
+            Token::ThematicBreak => {
+                let start = end - self.text_at(index).len();
+                self.push_node(Node::ThematicBreak(Position { start, end }));
+            }
+            Token::ListItemValue => {
+                if self.expect_first_list_item == Some(true) {
+                    if let Ok(value) = self.text_at(index).parse::<u32>() {
+                        if let FrameKind::List { start, .. } = &mut self.stack.last_mut().unwrap().kind {
+                            *start = Some(value);
+                        }
+                    }
+                }
+            }
+            Token::ListItemPrefix => {
+                self.expect_first_list_item = Some(false);
+            }
+            Token::CodeFencedFenceInfo => {
+                let info = self.text_at(index);
+                let lang = info.split_whitespace().next().map(str::to_string);
+                self.code_lang = lang;
+            }
+            Token::CodeFlowChunk => {
+                if let Some(value) = self.code_value.as_mut() {
+                    value.push_str(&self.text_at(index));
+                }
+            }
+            Token::CodeIndented | Token::CodeFenced => {
+                let value = self.code_value.take().unwrap_or_default();
+                let lang = self.code_lang.take();
+                let exit = &self.events[index];
+                let start = self.events[..index]
+                    .iter()
+                    .rposition(|event| {
+                        event.event_type == EventType::Enter && event.token_type == exit.token_type
+                    })
+                    .map_or(end, |enter| self.offset(enter));
+                self.push_node(Node::Code(CodeBlock {
+                    lang,
+                    value,
+                    position: Position { start, end },
+                }));
+            }
+            Token::ReferenceString => {
+                let value = self.text_at(index);
+                self.media_mut().reference_id = Some(value);
+            }
+            Token::DefinitionLabelString => {
+                let value = self.text_at(index);
+                self.media_mut().label_id = Some(value);
+            }
+            Token::DefinitionDestinationString | Token::ResourceDestinationString => {
+                let value = self.text_at(index);
+                self.media_mut().destination = Some(value);
+            }
+            Token::DefinitionTitleString | Token::ResourceTitleString => {
+                let value = self.text_at(index);
+                self.media_mut().title = Some(value);
+            }
+            Token::Definition => {
+                let (kind, _children, position) = self.pop_frame(end);
+                let media = match kind {
+                    FrameKind::Media(media) => media,
+                    _ => unreachable!("`Definition` always opens a media frame"),
+                };
+                let identifier = normalize_identifier(&media.label_id.unwrap_or_default());
+                let definition = Definition {
+                    identifier,
+                    destination: media.destination,
+                    title: media.title,
+                    position,
+                };
+                if !self
+                    .definitions
+                    .iter()
+                    .any(|existing| existing.identifier == definition.identifier)
+                {
+                    self.definitions.push(definition.clone());
+                }
+                self.push_node(Node::Definition(definition));
+            }
+            Token::Link | Token::Image => {
+                let (kind, children, position) = self.pop_frame(end);
+                let media = match kind {
+                    FrameKind::Media(media) => media,
+                    _ => unreachable!("`Link`/`Image` always open a media frame"),
+                };
+                self.finish_media(media, children, position);
+            }
+            Token::HeadingAtx => {
+                let (kind, children, position) = self.pop_frame(end);
+                let depth = match kind {
+                    FrameKind::Heading(depth) => depth.max(1),
+                    _ => unreachable!("`HeadingAtx` always opens a heading frame"),
+                };
+                self.push_node(Node::Heading(Heading {
+                    depth,
+                    children,
+                    position,
+                }));
+            }
+            Token::HeadingSetextText => {
+                let (kind, children, position) = self.pop_frame(end);
+                assert!(
+                    matches!(kind, FrameKind::Heading(_)),
+                    "`HeadingSetextText` always opens a heading frame"
+                );
+                self.pending_heading_setext = Some((children, position));
+            }
+            Token::HeadingSetextUnderline => {
+                let (children, mut position) = self
+                    .pending_heading_setext
+                    .take()
+                    .expect("`HeadingSetextText` must be seen before its underline");
+                let head = codes_from_span(self.codes, &from_exit_event(self.events, index))[0];
+                let depth = if head == Code::Char('-') { 2 } else { 1 };
+                position.end = end;
+                self.push_node(Node::Heading(Heading {
+                    depth,
+                    children,
+                    position,
+                }));
+            }
+            Token::Paragraph => {
+                let (_kind, children, position) = self.pop_frame(end);
+                self.push_node(Node::Paragraph(Parent { children, position }));
+            }
+            Token::BlockQuote => {
+                let (_kind, children, position) = self.pop_frame(end);
+                self.push_node(Node::BlockQuote(Parent { children, position }));
+            }
+            Token::ListOrdered | Token::ListUnordered => {
+                let (kind, children, position) = self.pop_frame(end);
+                let (ordered, start) = match kind {
+                    FrameKind::List { ordered, start } => (ordered, start),
+                    _ => unreachable!("`ListOrdered`/`ListUnordered` always open a list frame"),
+                };
+                self.push_node(Node::List(List {
+                    ordered,
+                    start,
+                    children,
+                    position,
+                }));
+            }
+            Token::ListItem => {
+                let (_kind, children, position) = self.pop_frame(end);
+                self.push_node(Node::ListItem(Parent { children, position }));
+            }
+            Token::Emphasis => {
+                let (_kind, children, position) = self.pop_frame(end);
+                self.push_node(Node::Emphasis(Parent { children, position }));
+            }
+            Token::Strong => {
+                let (_kind, children, position) = self.pop_frame(end);
+                self.push_node(Node::Strong(Parent { children, position }));
+            }
+            _ => {}
+        }
+    }
+
+    /// Resolve a finished link or image against `definitions`, falling back
+    /// to the reference's literal bracket text when nothing matches —
+    /// mirrors `compiler::on_exit_media`, minus its `broken_link_callback`
+    /// hook, which has no AST-level equivalent yet.
+    fn finish_media(&mut self, media: MediaBuilder, children: Vec<Node>, position: Position) {
+        let id = media
+            .reference_id
+            .or(media.label_id)
+            .map(|id| normalize_identifier(&id));
+
+        let (destination, title) = if media.destination.is_some() {
+            (media.destination, media.title)
+        } else if let Some(id) = &id {
+            match self.definitions.iter().find(|d| &d.identifier == id) {
+                Some(definition) => (definition.destination.clone(), definition.title.clone()),
+                None => {
+                    let label = flatten_text(&children);
+                    self.push_text(format!("[{}]", label), position);
+                    return;
+                }
+            }
+        } else {
+            (None, None)
+        };
+
+        if media.image {
+            self.push_node(Node::Image(Image {
+                destination,
+                title,
+                alt: flatten_text(&children),
+                position,
+            }));
+        } else {
+            self.push_node(Node::Link(Link {
+                destination,
+                title,
+                children,
+                position,
+            }));
+        }
+    }
+
+    fn finish(mut self) -> Node {
+        let end = if self.events.is_empty() {
+            0
+        } else {
+            self.offset(self.events.len() - 1)
+        };
+        let (_kind, children, position) = self.pop_frame(end);
+        Node::Root(Parent { children, position })
+    }
+}
+
+/// Flatten a node list down to its plain text, the way an `<img alt>` does:
+/// tags are dropped, their text content is kept.
+fn flatten_text(nodes: &[Node]) -> String {
+    let mut out = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(&text.value),
+            Node::Emphasis(parent) | Node::Strong(parent) => out.push_str(&flatten_text(&parent.children)),
+            Node::Link(link) => out.push_str(&flatten_text(&link.children)),
+            Node::Image(image) => out.push_str(&image.alt),
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Turn an event stream into a markdown syntax tree.
+///
+/// Definitions are collected in a first pass, exactly like
+/// `compiler::compile_internal`, so that reference links and images defined
+/// later in the source still resolve.
+pub fn to_mdast(events: &[Event], codes: &[Code]) -> Node {
+    // First pass: build only the definitions.
+    let mut collector = Builder::new(events, codes);
+    let mut definition_inside = false;
+    for (index, event) in events.iter().enumerate() {
+        let enter = event.event_type == EventType::Enter;
+        if event.token_type == Token::Definition && enter {
+            definition_inside = true;
+        }
+        if definition_inside && enter {
+            collector.enter(index);
+        } else if definition_inside {
+            collector.exit(index);
+        }
+        if event.token_type == Token::Definition && !enter {
+            definition_inside = false;
+        }
+    }
+    let mut builder = Builder::new(events, codes);
+    builder.definitions = collector.definitions;
+    let mut index = 0;
+    while index < events.len() {
+        let event = &events[index];
+        if event.event_type == EventType::Enter {
+            builder.enter(index);
+        } else {
+            builder.exit(index);
+        }
+        index += 1;
+    }
+    builder.finish()
+}
+
+/// Render a syntax tree back to HTML, with the tags, attributes and
+/// escaping [`crate::compiler::compile`] writes for the covered subset of
+/// nodes, the line ending `compile` inserts before a block element that
+/// doesn't start the output or follow a line ending, and `compile`'s
+/// default (`allow_dangerous_protocol: false`) link/image destination
+/// sanitizing. The output is not always identical to `compile`'s: the tree
+/// doesn't record tightness, so list items always render as loose (with
+/// `<p>`); the line endings `compile` writes before `</li>`, `</ol>`/`</ul>`
+/// and `</blockquote>` are not written; the line ending after a definition,
+/// which `compile` drops, is kept; and hard line breaks are not turned into
+/// `<br />`.
+pub fn to_html(node: &Node) -> String {
+    let mut out = String::new();
+    render(node, &mut out);
+    out
+}
+
+fn render(node: &Node, out: &mut String) {
+    match node {
+        Node::Root(parent) => render_all(&parent.children, out),
+        Node::Paragraph(parent) => wrap("p", &parent.children, out),
+        Node::Heading(heading) => wrap(
+            &format!("h{}", heading.depth.clamp(1, 6)),
+            &heading.children,
+            out,
+        ),
+        Node::ThematicBreak(_) => out.push_str("<hr />"),
+        Node::BlockQuote(parent) => wrap("blockquote", &parent.children, out),
+        Node::List(list) => {
+            let tag = if list.ordered { "ol" } else { "ul" };
+            out.push('<');
+            out.push_str(tag);
+            if let Some(start) = list.start {
+                if start != 1 {
+                    out.push_str(&format!(" start=\"{}\"", start));
+                }
+            }
+            out.push('>');
+            render_all(&list.children, out);
+            out.push_str("</");
+            out.push_str(tag);
+            out.push('>');
+        }
+        Node::ListItem(parent) => wrap("li", &parent.children, out),
+        Node::Code(code) => {
+            out.push_str("<pre><code");
+            if let Some(lang) = &code.lang {
+                out.push_str(&format!(" class=\"language-{}\"", encode_text(lang)));
+            }
+            out.push('>');
+            out.push_str(&encode_text(&code.value));
+            // Like `compile`, end code that has content with a line ending.
+            if !code.value.is_empty() && !code.value.ends_with(['\n', '\r']) {
+                out.push('\n');
+            }
+            out.push_str("</code></pre>");
+        }
+        Node::Definition(_) => {}
+        Node::Emphasis(parent) => wrap("em", &parent.children, out),
+        Node::Strong(parent) => wrap("strong", &parent.children, out),
+        Node::Link(link) => {
+            let href = sanitize_uri(
+                link.destination.as_deref().unwrap_or(""),
+                &Some(SAFE_PROTOCOL_HREF.to_vec()),
+            );
+            out.push_str(&format!(
+                "<a href=\"{}\"{}>",
+                encode_text(&href),
+                link.title
+                    .as_ref()
+                    .map_or_else(String::new, |title| format!(" title=\"{}\"", encode_text(title))),
+            ));
+            render_all(&link.children, out);
+            out.push_str("</a>");
+        }
+        Node::Image(image) => {
+            let src = sanitize_uri(
+                image.destination.as_deref().unwrap_or(""),
+                &Some(SAFE_PROTOCOL_SRC.to_vec()),
+            );
+            out.push_str(&format!(
+                "<img src=\"{}\" alt=\"{}\"{} />",
+                encode_text(&src),
+                encode_text(&image.alt),
+                image
+                    .title
+                    .as_ref()
+                    .map_or_else(String::new, |title| format!(" title=\"{}\"", encode_text(title))),
+            ));
+        }
+        Node::Text(text) => out.push_str(&encode_text(&text.value)),
+    }
+}
+
+fn render_all(nodes: &[Node], out: &mut String) {
+    for node in nodes {
+        // Mirrors `CompileContext::line_ending_if_needed`, called before
+        // every block element `compile` writes: one line ending between
+        // sibling block nodes, none at the very start of a buffer.
+        if is_block(node) && !out.is_empty() && !out.ends_with(['\n', '\r']) {
+            out.push('\n');
+        }
+        render(node, out);
+    }
+}
+
+/// Whether `node` is a block element that `compile` separates from its
+/// siblings with a line ending. [`Node::Definition`] is excluded: like in
+/// `compile`, it produces no visible output at all.
+fn is_block(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::Paragraph(_)
+            | Node::Heading(_)
+            | Node::ThematicBreak(_)
+            | Node::BlockQuote(_)
+            | Node::List(_)
+            | Node::ListItem(_)
+            | Node::Code(_)
+    )
+}
+
+fn wrap(tag: &str, children: &[Node], out: &mut String) {
+    out.push('<');
+    out.push_str(tag);
+    out.push('>');
+    render_all(children, out);
+    out.push_str("</");
+    out.push_str(tag);
+    out.push('>');
+}
+
+/// Minimal HTML-escaping for the handful of characters `compiler::encode`
+/// also escapes; kept local so this module doesn't depend on `compiler`'s
+/// private helpers.
+fn encode_text(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}